@@ -1,7 +1,8 @@
 //! Check make results
 
 use super::*;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use object::{read::archive::ArchiveFile, Object, ObjectSymbol, SymbolKind};
 use std::{
     collections::HashSet,
     fs,
@@ -10,7 +11,10 @@ use std::{
     path::*,
 };
 
-/// Parse compiler linker flags, `-L` and `-l`
+/// Parse compiler linker flags, `-L` and `-l`, along with the flags OpenBLAS's
+/// generated link lines also use: `-framework`, `-Wl,-rpath,`, and the
+/// `-Wl,-Bstatic`/`-Wl,-Bdynamic` toggles that group the `-l` entries
+/// following them.
 ///
 /// - Search paths defined by `-L` will be removed if not exists,
 ///   and will be canonicalize
@@ -20,10 +24,24 @@ use std::{
 /// let info = LinkInfo::parse("-L/usr/lib/gcc/x86_64-pc-linux-gnu/10.2.0 -L/usr/lib/gcc/x86_64-pc-linux-gnu/10.2.0/../../../../lib -L/lib/../lib -L/usr/lib/../lib -L/usr/lib/gcc/x86_64-pc-linux-gnu/10.2.0/../../..  -lc");
 /// assert_eq!(info.libs, vec!["c"]);
 /// ```
+///
+/// ```
+/// use openblas_build::*;
+/// let info = LinkInfo::parse("-Wl,-Bstatic -lgfortran -Wl,-Bdynamic -lc -framework Accelerate -Wl,-rpath,/opt/openblas/lib");
+/// assert_eq!(info.static_libs, vec!["gfortran"]);
+/// assert_eq!(info.libs, vec!["c"]);
+/// assert_eq!(info.frameworks, vec!["Accelerate"]);
+/// assert_eq!(info.rpaths, vec![std::path::PathBuf::from("/opt/openblas/lib")]);
+/// ```
 #[derive(Debug, Clone, Default)]
 pub struct LinkInfo {
     pub search_paths: Vec<PathBuf>,
+    /// Libraries linked dynamically, i.e. not under a `-Wl,-Bstatic` toggle
     pub libs: Vec<String>,
+    /// Libraries linked statically, i.e. under a `-Wl,-Bstatic` toggle
+    pub static_libs: Vec<String>,
+    pub frameworks: Vec<String>,
+    pub rpaths: Vec<PathBuf>,
 }
 
 fn as_sorted_vec<T: Hash + Ord>(set: HashSet<T>) -> Vec<T> {
@@ -32,26 +50,101 @@ fn as_sorted_vec<T: Hash + Ord>(set: HashSet<T>) -> Vec<T> {
     v
 }
 
+/// Normalize a library filename, or a Mach-O install name (an absolute path
+/// or an `@rpath`/`@loader_path`/`@executable_path`-relative one), to its
+/// bare link name. Takes the last `/`-separated component, strips a leading
+/// `lib` prefix, and cuts at the first `.`, which removes any of the known
+/// library extensions (`.so`, `.a`, `.dll`, `.lib`, `.dylib`, `.framework`,
+/// `.tbd`) along with any version digits before or after it, e.g. the
+/// `.so.0.3.21` of `libopenblas.so.0.3.21` or the `.0.dylib` of
+/// `libopenblas.0.dylib`.
+///
+/// ```
+/// # use openblas_build::cleanup_lib_filename;
+/// assert_eq!(cleanup_lib_filename("libopenblas.so.0.3.21"), "openblas");
+/// assert_eq!(cleanup_lib_filename("libgfortran.so.5"), "gfortran");
+/// assert_eq!(cleanup_lib_filename("libopenblas.a"), "openblas");
+/// assert_eq!(cleanup_lib_filename("/usr/lib/libopenblas.0.dylib"), "openblas");
+/// assert_eq!(cleanup_lib_filename("@rpath/libopenblas.dylib"), "openblas");
+/// ```
+pub fn cleanup_lib_filename(name: &str) -> String {
+    let name = name.rsplit('/').next().unwrap_or(name);
+    let name = name.strip_prefix("lib").unwrap_or(name);
+    name.split('.').next().unwrap_or(name).to_string()
+}
+
 impl LinkInfo {
     pub fn parse(line: &str) -> Self {
         let mut search_paths = HashSet::new();
         let mut libs = HashSet::new();
-        for entry in line.split(" ") {
+        let mut static_libs = HashSet::new();
+        let mut frameworks = HashSet::new();
+        let mut rpaths = HashSet::new();
+        let mut binding = LinkKind::Dynamic;
+
+        let mut entries = line.split(" ");
+        while let Some(entry) = entries.next() {
             if entry.starts_with("-L") {
                 let path = PathBuf::from(entry.trim_start_matches("-L"));
                 if !path.exists() {
                     continue;
                 }
                 search_paths.insert(path.canonicalize().expect("Failed to canonicalize path"));
-            }
-            if entry.starts_with("-l") {
-                libs.insert(entry.trim_start_matches("-l").into());
+            } else if entry == "-Wl,-Bstatic" {
+                binding = LinkKind::Static;
+            } else if entry == "-Wl,-Bdynamic" {
+                binding = LinkKind::Dynamic;
+            } else if entry == "-framework" {
+                if let Some(name) = entries.next() {
+                    frameworks.insert(name.into());
+                }
+            } else if let Some(rpath) = entry.strip_prefix("-Wl,-rpath,") {
+                rpaths.insert(PathBuf::from(rpath));
+            } else if let Some(name) = entry.strip_prefix("-l") {
+                match binding {
+                    LinkKind::Static => static_libs.insert(name.into()),
+                    LinkKind::Dynamic => libs.insert(name.into()),
+                };
             }
         }
         LinkInfo {
             search_paths: as_sorted_vec(search_paths),
             libs: as_sorted_vec(libs),
+            static_libs: as_sorted_vec(static_libs),
+            frameworks: as_sorted_vec(frameworks),
+            rpaths: as_sorted_vec(rpaths),
+        }
+    }
+
+    /// Cargo directives for everything this `LinkInfo` captured: search
+    /// paths, rpaths, frameworks, and libraries (grouped `static`/`dylib` by
+    /// the `-Bstatic`/`-Bdynamic` state each was parsed under).
+    pub fn cargo_link_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for path in &self.search_paths {
+            lines.push(format!("cargo:rustc-link-search={}", path.display()));
+        }
+        for path in &self.rpaths {
+            lines.push(format!("cargo:rustc-link-arg=-Wl,-rpath,{}", path.display()));
+        }
+        for framework in &self.frameworks {
+            lines.push(format!("cargo:rustc-link-lib=framework={}", framework));
+        }
+        for lib in &self.static_libs {
+            lines.push(format!(
+                "cargo:rustc-link-lib={}={}",
+                LinkKind::Static.as_cargo_kind(),
+                lib
+            ));
+        }
+        for lib in &self.libs {
+            lines.push(format!(
+                "cargo:rustc-link-lib={}={}",
+                LinkKind::Dynamic.as_cargo_kind(),
+                lib
+            ));
         }
+        lines
     }
 }
 
@@ -89,6 +182,29 @@ impl MakeConf {
         }
         Ok(detail)
     }
+
+    /// Cargo `cargo:rustc-link-search=`/`cargo:rustc-link-lib=` directives
+    /// needed to link against `detail`.
+    ///
+    /// For a static archive, `CEXTRALIB`/`FEXTRALIB` are also emitted since
+    /// they are not transitively pulled in the way they are for a shared
+    /// library.
+    pub fn cargo_link_lines(&self, detail: &LibDetail) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(dir) = detail.path.parent() {
+            lines.push(format!("cargo:rustc-link-search={}", dir.display()));
+        }
+        let kind = detail.link_kind();
+        lines.push(format!(
+            "cargo:rustc-link-lib={}=openblas",
+            kind.as_cargo_kind()
+        ));
+        if kind == LinkKind::Static {
+            lines.extend(self.c_extra_libs.cargo_link_lines());
+            lines.extend(self.f_extra_libs.cargo_link_lines());
+        }
+        lines
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -96,12 +212,13 @@ pub struct LibDetail {
     /// File path of library
     path: PathBuf,
 
-    /// Linked shared libraries. It will be empty if the library is static.
-    /// Use `objdump -p` external command.
+    /// Dependency libraries read from the ELF `DT_NEEDED` entries, Mach-O
+    /// `LC_LOAD_DYLIB` load commands, or the PE import directory. It will be
+    /// empty if the library is a static archive.
     libs: Vec<String>,
 
-    /// Global "T" symbols in the text (code) section of library.
-    /// Use `nm -g` external command.
+    /// Global defined symbols in the code section of the library
+    /// (ELF `STB_GLOBAL` symbols in `.text`, Mach-O/PE exported text symbols).
     symbols: Vec<String>,
 }
 
@@ -111,56 +228,50 @@ impl LibDetail {
         if !path.exists() {
             panic!("File not found: {}", path.display());
         }
+        Self::inspect(path).expect("Failed to parse library file")
+    }
 
-        let nm_out = Command::new("nm")
-            .arg("-g")
-            .arg(path)
-            .output()
-            .expect("nm cannot be started");
-
-        // assumes `nm` output like following:
-        //
-        // ```
-        // 0000000000909b30 T zupmtr_
-        // ```
-        let mut symbols: Vec<_> = nm_out
-            .stdout
-            .lines()
-            .flat_map(|line| {
-                let line = line.ok()?;
-                let entry: Vec<_> = line.trim().split(" ").collect();
-                if entry.len() != 3 && entry[2] == "T" {
-                    None
-                } else {
-                    Some(entry[2].into())
-                }
-            })
-            .collect();
-        symbols.sort(); // sort alphabetically
-
-        let mut libs: Vec<_> = Command::new("objdump")
-            .arg("-p")
-            .arg(path)
-            .output()
-            .expect("objdump cannot start")
-            .stdout
-            .lines()
-            .flat_map(|line| {
-                let line = line.ok()?;
-                if line.trim().starts_with("NEEDED") {
-                    Some(line.trim().trim_start_matches("NEEDED").trim().into())
-                } else {
-                    None
+    fn inspect(path: &Path) -> Result<Self> {
+        let data = fs::read(path)
+            .with_context(|| format!("Failed to read library file: {}", path.display()))?;
+
+        // A static archive (`.a`/`.lib`) has no dynamic dependency list; its
+        // symbols are the union of the symbols defined by its members.
+        if let Ok(archive) = ArchiveFile::parse(&*data) {
+            let mut symbols = Vec::new();
+            for member in archive.members() {
+                let member = member.with_context(|| {
+                    format!("Failed to read archive member in {}", path.display())
+                })?;
+                let member_data = member.data(&*data).with_context(|| {
+                    format!("Failed to read archive member in {}", path.display())
+                })?;
+                if let Ok(file) = object::File::parse(member_data) {
+                    symbols.extend(exported_text_symbols(&file));
                 }
-            })
+            }
+            symbols.sort();
+            symbols.dedup();
+            return Ok(LibDetail {
+                path: path.into(),
+                libs: Vec::new(),
+                symbols,
+            });
+        }
+
+        let file = object::File::parse(&*data)
+            .with_context(|| format!("Failed to parse library file: {}", path.display()))?;
+        let mut libs: Vec<String> = needed_libraries(&file, &data)
+            .iter()
+            .map(|lib| cleanup_lib_filename(lib))
             .collect();
         libs.sort();
-
-        LibDetail {
+        let symbols = exported_text_symbols(&file);
+        Ok(LibDetail {
             path: path.into(),
             libs,
             symbols,
-        }
+        })
     }
 
     pub fn has_cblas(&self) -> bool {
@@ -191,15 +302,154 @@ impl LibDetail {
     }
 
     pub fn has_lib(&self, name: &str) -> bool {
-        for lib in &self.libs {
-            if let Some(stem) = lib.split(".").next() {
-                if stem == format!("lib{}", name) {
-                    return true;
+        // `self.libs` is already normalized by `cleanup_lib_filename` in
+        // `inspect`; comparing directly avoids over-stripping a bare name
+        // that happens to start with `lib` itself (e.g. `libusb-1.0`).
+        self.libs.iter().any(|lib| lib == name)
+    }
+
+    /// Whether this artifact is a static archive or a dynamic library.
+    ///
+    /// A `.a`/`.lib` archive, or any file with no dynamic dependencies, is
+    /// considered static; everything else is dynamic.
+    pub fn link_kind(&self) -> LinkKind {
+        let ext = self.path.extension().and_then(|ext| ext.to_str());
+        if matches!(ext, Some("a") | Some("lib")) || self.libs.is_empty() {
+            LinkKind::Static
+        } else {
+            LinkKind::Dynamic
+        }
+    }
+}
+
+/// Whether an OpenBLAS artifact is a static archive or a dynamic library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Static,
+    Dynamic,
+}
+
+impl LinkKind {
+    /// Cargo `rustc-link-lib` kind, i.e. `static` or `dylib`
+    fn as_cargo_kind(self) -> &'static str {
+        match self {
+            LinkKind::Static => "static",
+            LinkKind::Dynamic => "dylib",
+        }
+    }
+}
+
+/// Global defined symbols in the text section of a parsed object file,
+/// equivalent to the "T" entries of `nm -g`.
+fn global_text_symbols(file: &object::File) -> Vec<String> {
+    let mut symbols: Vec<String> = file
+        .symbols()
+        .filter(|sym| {
+            sym.is_definition() && sym.is_global() && sym.kind() == SymbolKind::Text
+        })
+        .filter_map(|sym| sym.name().ok().map(String::from))
+        .collect();
+    symbols.sort();
+    symbols
+}
+
+/// Global defined text symbols of a parsed object file, read from the
+/// format-specific table that actually carries them for a shared library:
+/// the regular symbol table for ELF/Mach-O (see [`global_text_symbols`]),
+/// but the PE export directory for PE/COFF, since release DLLs ship with a
+/// stripped COFF symbol table and their exported names live only in the
+/// export directory (reachable via `Object::exports()`).
+fn exported_text_symbols(file: &object::File) -> Vec<String> {
+    use object::File::*;
+    match file {
+        Pe32(_) | Pe64(_) => pe_export_symbols(file),
+        _ => global_text_symbols(file),
+    }
+}
+
+fn pe_export_symbols(file: &object::File) -> Vec<String> {
+    let mut symbols: Vec<String> = file
+        .exports()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|export| std::str::from_utf8(export.name()).ok().map(String::from))
+        .collect();
+    symbols.sort();
+    symbols
+}
+
+/// Dependency library names read from the format-specific dependency table:
+/// ELF `DT_NEEDED`, Mach-O `LC_LOAD_DYLIB`, or the PE import directory.
+fn needed_libraries(file: &object::File, data: &[u8]) -> Vec<String> {
+    use object::File::*;
+
+    match file {
+        Elf32(elf) => elf_needed_libraries(elf, data),
+        Elf64(elf) => elf_needed_libraries(elf, data),
+        MachO32(macho) => macho_needed_libraries(macho.macho_header(), macho.endian(), data),
+        MachO64(macho) => macho_needed_libraries(macho.macho_header(), macho.endian(), data),
+        Pe32(pe) => pe_needed_libraries(pe),
+        Pe64(pe) => pe_needed_libraries(pe),
+        _ => Vec::new(),
+    }
+}
+
+fn elf_needed_libraries<Elf: object::read::elf::FileHeader>(
+    elf: &object::read::elf::ElfFile<Elf>,
+    data: &[u8],
+) -> Vec<String> {
+    use object::read::elf::Dyn;
+
+    let endian = elf.endian();
+    let sections = elf.elf_section_table();
+    let Ok(Some((dynamic, link))) = sections.dynamic(endian, data) else {
+        return Vec::new();
+    };
+    let Ok(strings) = sections.strings(endian, data, link) else {
+        return Vec::new();
+    };
+    dynamic
+        .iter()
+        .filter(|d| d.tag32(endian) == Some(object::elf::DT_NEEDED))
+        .filter_map(|d| d.string(endian, strings).ok())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect()
+}
+
+fn macho_needed_libraries<Mach: object::read::macho::MachHeader>(
+    header: &Mach,
+    endian: Mach::Endian,
+    data: &[u8],
+) -> Vec<String> {
+    let mut needed = Vec::new();
+    let mut commands = match header.load_commands(endian, data, 0) {
+        Ok(commands) => commands,
+        Err(_) => return needed,
+    };
+    while let Ok(Some(command)) = commands.next() {
+        if let Ok(Some(dylib)) = command.dylib() {
+            if let Ok(name) = command.string(endian, dylib.dylib.name) {
+                needed.push(String::from_utf8_lossy(name).into_owned());
+            }
+        }
+    }
+    needed
+}
+
+fn pe_needed_libraries<'data, Pe: object::read::pe::ImageNtHeaders>(
+    pe: &object::read::pe::PeFile<'data, Pe>,
+) -> Vec<String> {
+    let mut needed = Vec::new();
+    if let Ok(Some(import_table)) = pe.import_table() {
+        if let Ok(mut descriptors) = import_table.descriptors() {
+            while let Ok(Some(descriptor)) = descriptors.next() {
+                if let Ok(name) = import_table.name(descriptor.name.get(object::LittleEndian)) {
+                    needed.push(String::from_utf8_lossy(name).into_owned());
                 }
-            };
+            }
         }
-        return false;
     }
+    needed
 }
 
 #[cfg(test)]
@@ -221,4 +471,45 @@ mod tests {
         let detail = MakeConf::new(path).unwrap();
         assert!(detail.no_fortran);
     }
+
+    /// `sample_dynamic.so` is a synthetic ELF64 shared object with a single
+    /// `DT_NEEDED` entry (`libfoo.so.1`) and a single global text symbol
+    /// (`cblas_sgemm`), used to exercise the `object`-crate parsing path
+    /// without depending on an actual OpenBLAS build.
+    #[test]
+    fn detail_from_dynamic_lib() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sample_dynamic.so");
+        assert!(path.exists());
+        let detail = LibDetail::new(path);
+        assert_eq!(detail.link_kind(), LinkKind::Dynamic);
+        assert!(detail.has_lib("foo"));
+        assert!(detail.has_cblas());
+        assert!(!detail.has_lapack());
+    }
+
+    /// `sample_static.a` is a synthetic `ar` archive containing a single
+    /// relocatable ELF64 object that defines the global text symbol
+    /// `dsyev_`, used to exercise the archive-member parsing path.
+    #[test]
+    fn detail_from_static_lib() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sample_static.a");
+        assert!(path.exists());
+        let detail = LibDetail::new(path);
+        assert_eq!(detail.link_kind(), LinkKind::Static);
+        assert!(detail.has_lapack());
+        assert!(!detail.has_cblas());
+    }
+
+    /// `sample_dynamic.dll` is a synthetic PE32+ DLL with a stripped COFF
+    /// symbol table (as real release OpenBLAS builds ship) and a single
+    /// export-directory entry (`cblas_sgemm`), used to exercise the PE
+    /// export-table symbol path rather than the regular symbol table.
+    #[test]
+    fn detail_from_pe_dll() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sample_dynamic.dll");
+        assert!(path.exists());
+        let detail = LibDetail::new(path);
+        assert!(detail.has_cblas());
+        assert!(!detail.has_lapack());
+    }
 }